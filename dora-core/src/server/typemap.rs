@@ -2,11 +2,24 @@
 
 use std::{
     any::{Any, TypeId},
-    collections::HashMap,
     fmt,
     hash::{BuildHasherDefault, Hasher},
+    marker::PhantomData,
 };
 
+// The `hash_map` module of whichever backend is selected: `std`'s by
+// default, or `hashbrown`'s under the `hashbrown` feature. Aliased under
+// one name so the `Entry` API below compiles against either backend.
+#[cfg(feature = "hashbrown")]
+use hashbrown::hash_map as raw_hash_map;
+#[cfg(not(feature = "hashbrown"))]
+use std::collections::hash_map as raw_hash_map;
+
+#[cfg(not(feature = "hashbrown"))]
+type RawHashMap<K, V, S> = std::collections::HashMap<K, V, S>;
+#[cfg(feature = "hashbrown")]
+type RawHashMap<K, V, S> = hashbrown::HashMap<K, V, S>;
+
 /// A TypeId is already a hash, so we don't need to hash it
 #[derive(Default)]
 struct TypeIdHash(u64);
@@ -27,7 +40,15 @@ impl Hasher for TypeIdHash {
     }
 }
 
-type AnyTypeMap = HashMap<TypeId, Box<dyn Any + Send + Sync>, BuildHasherDefault<TypeIdHash>>;
+/// An entry in the map's inner storage: the boxed value alongside the
+/// `type_name` captured at insertion time, since a bare `TypeId` can't be
+/// turned back into a readable name for [`fmt::Debug`].
+struct TypeMapEntry {
+    type_name: &'static str,
+    value: Box<dyn Any + Send + Sync>,
+}
+
+type AnyTypeMap = RawHashMap<TypeId, TypeMapEntry, BuildHasherDefault<TypeIdHash>>;
 
 /// This is a HashMap of values, stored based on [`TypeId`]. Every type has a
 /// unique `TypeId` generated by the compiler, we are using this id to store in
@@ -43,6 +64,10 @@ type AnyTypeMap = HashMap<TypeId, Box<dyn Any + Send + Sync>, BuildHasherDefault
 /// ```
 ///
 /// [`TypeId`]: std::any::TypeId
+///
+/// Backed by [`std::collections::HashMap`] by default; enable the
+/// `hashbrown` feature to back it with `hashbrown`'s `HashMap` instead. The
+/// public API is identical either way.
 #[derive(Default)]
 pub struct TypeMap {
     map: Option<Box<AnyTypeMap>>,
@@ -55,6 +80,106 @@ impl TypeMap {
         TypeMap { map: None }
     }
 
+    /// Make a new `TypeMap` with the inner `HashMap` preallocated to hold
+    /// at least `capacity` elements without reallocating.
+    ///
+    /// ```
+    /// # use dora_core::server::typemap::TypeMap;
+    /// let map = TypeMap::with_capacity(10);
+    /// assert!(map.capacity() >= 10);
+    /// ```
+    pub fn with_capacity(capacity: usize) -> TypeMap {
+        TypeMap {
+            map: Some(Box::new(RawHashMap::with_capacity_and_hasher(
+                capacity,
+                BuildHasherDefault::default(),
+            ))),
+        }
+    }
+
+    /// Returns the number of types currently stored in the map.
+    ///
+    /// ```
+    /// # use dora_core::server::typemap::TypeMap;
+    /// let mut map = TypeMap::new();
+    /// assert_eq!(map.len(), 0);
+    /// map.insert(10_usize);
+    /// assert_eq!(map.len(), 1);
+    /// ```
+    pub fn len(&self) -> usize {
+        self.map.as_ref().map_or(0, |map| map.len())
+    }
+
+    /// Returns `true` if the map holds no types.
+    ///
+    /// ```
+    /// # use dora_core::server::typemap::TypeMap;
+    /// let mut map = TypeMap::new();
+    /// assert!(map.is_empty());
+    /// map.insert(10_usize);
+    /// assert!(!map.is_empty());
+    /// ```
+    pub fn is_empty(&self) -> bool {
+        self.map.as_ref().is_none_or(|map| map.is_empty())
+    }
+
+    /// Returns the number of types the map can hold without reallocating.
+    ///
+    /// ```
+    /// # use dora_core::server::typemap::TypeMap;
+    /// let map = TypeMap::new();
+    /// assert_eq!(map.capacity(), 0);
+    /// ```
+    pub fn capacity(&self) -> usize {
+        self.map.as_ref().map_or(0, |map| map.capacity())
+    }
+
+    /// Returns `true` if a value of type `T` is present in the map.
+    ///
+    /// ```
+    /// # use dora_core::server::typemap::TypeMap;
+    /// let mut map = TypeMap::new();
+    /// assert!(!map.contains::<usize>());
+    /// map.insert(10_usize);
+    /// assert!(map.contains::<usize>());
+    /// ```
+    pub fn contains<T: Send + Sync + 'static>(&self) -> bool {
+        self.map
+            .as_ref()
+            .is_some_and(|map| map.contains_key(&TypeId::of::<T>()))
+    }
+
+    /// Reserves capacity for at least `additional` more types to be
+    /// inserted into the map.
+    ///
+    /// ```
+    /// # use dora_core::server::typemap::TypeMap;
+    /// let mut map = TypeMap::new();
+    /// map.reserve(10);
+    /// assert!(map.capacity() >= 10);
+    /// ```
+    pub fn reserve(&mut self, additional: usize) {
+        self.map
+            .get_or_insert_with(|| Box::new(RawHashMap::default()))
+            .reserve(additional);
+    }
+
+    /// Shrinks the capacity of the map as much as possible, without
+    /// affecting any currently stored types. A no-op if the map hasn't
+    /// been allocated yet.
+    ///
+    /// ```
+    /// # use dora_core::server::typemap::TypeMap;
+    /// let mut map = TypeMap::new();
+    /// map.reserve(10);
+    /// map.shrink_to_fit();
+    /// ```
+    pub fn shrink_to_fit(&mut self) {
+        if let Some(ref mut map) = self.map {
+            map.shrink_to_fit();
+        }
+    }
+
     /// Insert a type into the map. If the type already exists, it will be
     /// returned.
     ///
@@ -67,10 +192,16 @@ impl TypeMap {
     /// ```
     pub fn insert<T: Send + Sync + 'static>(&mut self, val: T) -> Option<T> {
         self.map
-            .get_or_insert_with(|| Box::new(HashMap::default()))
-            .insert(TypeId::of::<T>(), Box::new(val))
-            .and_then(|boxed| {
-                (boxed as Box<dyn Any + 'static>)
+            .get_or_insert_with(|| Box::new(RawHashMap::default()))
+            .insert(
+                TypeId::of::<T>(),
+                TypeMapEntry {
+                    type_name: std::any::type_name::<T>(),
+                    value: Box::new(val),
+                },
+            )
+            .and_then(|entry| {
+                (entry.value as Box<dyn Any + Send + Sync>)
                     .downcast()
                     .ok()
                     .map(|x| *x)
@@ -91,7 +222,7 @@ impl TypeMap {
         self.map
             .as_ref()
             .and_then(|map| map.get(&TypeId::of::<T>()))
-            .and_then(|boxed| (**boxed).downcast_ref::<T>())
+            .and_then(|entry| (&*entry.value as &dyn Any).downcast_ref::<T>())
     }
 
     /// Get a mutable reference to a type previously inserted
@@ -108,7 +239,7 @@ impl TypeMap {
         self.map
             .as_mut()
             .and_then(|map| map.get_mut(&TypeId::of::<T>()))
-            .and_then(|boxed| (**boxed).downcast_mut())
+            .and_then(|entry| (&mut *entry.value as &mut dyn Any).downcast_mut::<T>())
     }
 
     /// Remove a type
@@ -124,14 +255,40 @@ impl TypeMap {
         self.map
             .as_mut()
             .and_then(|map| map.remove(&TypeId::of::<T>()))
-            .and_then(|boxed| {
-                (boxed as Box<dyn Any + 'static>)
+            .and_then(|entry| {
+                (entry.value as Box<dyn Any + Send + Sync>)
                     .downcast()
                     .ok()
                     .map(|x| *x)
             })
     }
 
+    /// Get the given type's corresponding entry in the map for in-place
+    /// manipulation.
+    ///
+    /// ```
+    /// # use dora_core::server::typemap::TypeMap;
+    /// let mut map = TypeMap::new();
+    /// *map.entry::<usize>().or_insert(0) += 10;
+    /// assert_eq!(map.get::<usize>(), Some(&10));
+    /// ```
+    pub fn entry<T: Send + Sync + 'static>(&mut self) -> Entry<'_, T> {
+        match self
+            .map
+            .get_or_insert_with(|| Box::new(RawHashMap::default()))
+            .entry(TypeId::of::<T>())
+        {
+            raw_hash_map::Entry::Occupied(inner) => Entry::Occupied(OccupiedEntry {
+                inner,
+                marker: PhantomData,
+            }),
+            raw_hash_map::Entry::Vacant(inner) => Entry::Vacant(VacantEntry {
+                inner,
+                marker: PhantomData,
+            }),
+        }
+    }
+
     /// Clear the `TypeMap` of all inserted values.
     ///
     /// ```
@@ -149,8 +306,406 @@ impl TypeMap {
     }
 }
 
+/// ```
+/// # use dora_core::server::typemap::TypeMap;
+/// let mut map = TypeMap::new();
+/// map.insert(10_usize);
+/// assert_eq!(format!("{:?}", map), "{\"usize\"}");
+/// ```
 impl fmt::Debug for TypeMap {
     fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
-        f.debug_struct("TypeMap").finish()
+        let mut dbg = f.debug_set();
+        if let Some(map) = &self.map {
+            for entry in map.values() {
+                dbg.entry(&entry.type_name);
+            }
+        }
+        dbg.finish()
+    }
+}
+
+/// A view into a single entry in a [`TypeMap`], which may either be vacant
+/// or occupied.
+///
+/// This `enum` is constructed from the [`entry`] method on [`TypeMap`].
+///
+/// [`entry`]: TypeMap::entry
+pub enum Entry<'a, T> {
+    /// An occupied entry.
+    Occupied(OccupiedEntry<'a, T>),
+    /// A vacant entry.
+    Vacant(VacantEntry<'a, T>),
+}
+
+impl<'a, T: Send + Sync + 'static> Entry<'a, T> {
+    /// Ensures a value is in the entry by inserting the given value if
+    /// empty, and returns a mutable reference to the value in the entry.
+    pub fn or_insert(self, default: T) -> &'a mut T {
+        match self {
+            Entry::Occupied(entry) => entry.into_mut(),
+            Entry::Vacant(entry) => entry.insert(default),
+        }
+    }
+
+    /// Ensures a value is in the entry by inserting the result of the
+    /// given function if empty, and returns a mutable reference to the
+    /// value in the entry.
+    pub fn or_insert_with<F: FnOnce() -> T>(self, default: F) -> &'a mut T {
+        match self {
+            Entry::Occupied(entry) => entry.into_mut(),
+            Entry::Vacant(entry) => entry.insert(default()),
+        }
+    }
+}
+
+impl<'a, T: Send + Sync + Default + 'static> Entry<'a, T> {
+    /// Ensures a value is in the entry by inserting the default value if
+    /// empty, and returns a mutable reference to the value in the entry.
+    pub fn or_default(self) -> &'a mut T {
+        match self {
+            Entry::Occupied(entry) => entry.into_mut(),
+            Entry::Vacant(entry) => entry.insert(T::default()),
+        }
+    }
+}
+
+/// A view into an occupied entry in a [`TypeMap`]. It is part of the
+/// [`Entry`] enum.
+///
+/// `std`'s `OccupiedEntry` has no nameable hasher type parameter on
+/// stable (its third parameter is an unstable allocator one), while
+/// `hashbrown`'s does, so the field type is selected per backend.
+pub struct OccupiedEntry<'a, T> {
+    #[cfg(not(feature = "hashbrown"))]
+    inner: raw_hash_map::OccupiedEntry<'a, TypeId, TypeMapEntry>,
+    #[cfg(feature = "hashbrown")]
+    inner: raw_hash_map::OccupiedEntry<'a, TypeId, TypeMapEntry, BuildHasherDefault<TypeIdHash>>,
+    marker: PhantomData<fn(T)>,
+}
+
+impl<'a, T: Send + Sync + 'static> OccupiedEntry<'a, T> {
+    /// Gets a reference to the value in the entry.
+    pub fn get(&self) -> &T {
+        (&*self.inner.get().value as &dyn Any)
+            .downcast_ref()
+            .expect("type mismatch in TypeMap entry")
+    }
+
+    /// Gets a mutable reference to the value in the entry.
+    pub fn get_mut(&mut self) -> &mut T {
+        (&mut *self.inner.get_mut().value as &mut dyn Any)
+            .downcast_mut()
+            .expect("type mismatch in TypeMap entry")
+    }
+
+    /// Converts the entry into a mutable reference to the value in the
+    /// entry with a lifetime bound to the map itself.
+    pub fn into_mut(self) -> &'a mut T {
+        (&mut *self.inner.into_mut().value as &mut dyn Any)
+            .downcast_mut()
+            .expect("type mismatch in TypeMap entry")
+    }
+
+    /// Sets the value of the entry, and returns the entry's old value.
+    pub fn insert(&mut self, value: T) -> T {
+        *(self
+            .inner
+            .insert(TypeMapEntry {
+                type_name: std::any::type_name::<T>(),
+                value: Box::new(value),
+            })
+            .value as Box<dyn Any + Send + Sync>)
+            .downcast()
+            .expect("type mismatch in TypeMap entry")
+    }
+
+    /// Takes the value out of the entry, and removes it from the map.
+    pub fn remove(self) -> T {
+        *(self.inner.remove().value as Box<dyn Any + Send + Sync>)
+            .downcast()
+            .expect("type mismatch in TypeMap entry")
+    }
+}
+
+/// A view into a vacant entry in a [`TypeMap`]. It is part of the [`Entry`]
+/// enum.
+pub struct VacantEntry<'a, T> {
+    #[cfg(not(feature = "hashbrown"))]
+    inner: raw_hash_map::VacantEntry<'a, TypeId, TypeMapEntry>,
+    #[cfg(feature = "hashbrown")]
+    inner: raw_hash_map::VacantEntry<'a, TypeId, TypeMapEntry, BuildHasherDefault<TypeIdHash>>,
+    marker: PhantomData<fn(T)>,
+}
+
+impl<'a, T: Send + Sync + 'static> VacantEntry<'a, T> {
+    /// Sets the value of the entry, and returns a mutable reference to it.
+    pub fn insert(self, value: T) -> &'a mut T {
+        (&mut *self
+            .inner
+            .insert(TypeMapEntry {
+                type_name: std::any::type_name::<T>(),
+                value: Box::new(value),
+            })
+            .value as &mut dyn Any)
+            .downcast_mut()
+            .expect("type mismatch in TypeMap entry")
+    }
+}
+
+/// A value that can be stored in a [`CloneTypeMap`], carrying enough
+/// capability to be cloned through its erased trait object.
+trait CloneAny: Any + Send + Sync {
+    /// Clone `self` into a new, boxed trait object.
+    fn clone_any(&self) -> Box<dyn CloneAny + Send + Sync>;
+}
+
+impl<T: Any + Clone + Send + Sync> CloneAny for T {
+    fn clone_any(&self) -> Box<dyn CloneAny + Send + Sync> {
+        Box::new(self.clone())
+    }
+}
+
+impl Clone for Box<dyn CloneAny + Send + Sync> {
+    fn clone(&self) -> Self {
+        (**self).clone_any()
+    }
+}
+
+type CloneAnyTypeMap =
+    RawHashMap<TypeId, Box<dyn CloneAny + Send + Sync>, BuildHasherDefault<TypeIdHash>>;
+
+/// A [`TypeMap`] variant whose values are required to implement [`Clone`],
+/// so the map itself can be cloned. Useful for server code that wants to
+/// snapshot accumulated request or connection state.
+///
+/// ```
+/// # use dora_core::server::typemap::CloneTypeMap;
+/// let mut map = CloneTypeMap::new();
+/// map.insert(10_usize);
+///
+/// let snapshot = map.clone();
+/// map.insert(20_usize);
+///
+/// assert_eq!(map.get::<usize>(), Some(&20_usize));
+/// assert_eq!(snapshot.get::<usize>(), Some(&10_usize));
+/// ```
+#[derive(Default)]
+pub struct CloneTypeMap {
+    map: Option<Box<CloneAnyTypeMap>>,
+}
+
+impl CloneTypeMap {
+    /// Make a new `CloneTypeMap`, does zero allocation
+    #[inline]
+    pub fn new() -> CloneTypeMap {
+        CloneTypeMap { map: None }
+    }
+
+    /// Insert a type into the map. If the type already exists, it will be
+    /// returned.
+    ///
+    /// ```
+    /// # use dora_core::server::typemap::CloneTypeMap;
+    /// let mut map = CloneTypeMap::new();
+    /// assert!(map.insert(10_usize).is_none());
+    /// assert!(map.insert(10_u8).is_none());
+    /// assert_eq!(map.insert(15_usize), Some(10_usize));
+    /// ```
+    pub fn insert<T: Any + Clone + Send + Sync>(&mut self, val: T) -> Option<T> {
+        self.map
+            .get_or_insert_with(|| Box::new(RawHashMap::default()))
+            .insert(TypeId::of::<T>(), Box::new(val))
+            .and_then(|boxed| {
+                (boxed as Box<dyn Any + Send + Sync>)
+                    .downcast()
+                    .ok()
+                    .map(|x| *x)
+            })
+    }
+
+    /// Get a reference to a type previously inserted
+    ///
+    /// ```
+    /// # use dora_core::server::typemap::CloneTypeMap;
+    /// let mut map = CloneTypeMap::new();
+    /// assert!(map.get::<i32>().is_none());
+    /// map.insert(5i32);
+    ///
+    /// assert_eq!(map.get::<i32>(), Some(&5i32));
+    /// ```
+    pub fn get<T: Any + Clone + Send + Sync>(&self) -> Option<&T> {
+        self.map
+            .as_ref()
+            .and_then(|map| map.get(&TypeId::of::<T>()))
+            .and_then(|boxed| (&**boxed as &dyn Any).downcast_ref::<T>())
+    }
+
+    /// Get a mutable reference to a type previously inserted
+    ///
+    /// ```
+    /// # use dora_core::server::typemap::CloneTypeMap;
+    /// let mut map = CloneTypeMap::new();
+    /// map.insert(String::from("Hello"));
+    /// map.get_mut::<String>().unwrap().push_str(" World");
+    ///
+    /// assert_eq!(map.get::<String>().unwrap(), "Hello World");
+    /// ```
+    pub fn get_mut<T: Any + Clone + Send + Sync>(&mut self) -> Option<&mut T> {
+        self.map
+            .as_mut()
+            .and_then(|map| map.get_mut(&TypeId::of::<T>()))
+            .and_then(|boxed| (&mut **boxed as &mut dyn Any).downcast_mut::<T>())
+    }
+
+    /// Remove a type
+    ///
+    /// ```
+    /// # use dora_core::server::typemap::CloneTypeMap;
+    /// let mut map = CloneTypeMap::new();
+    /// map.insert(10_usize);
+    /// assert_eq!(map.remove::<usize>(), Some(10_usize));
+    /// assert!(map.get::<usize>().is_none());
+    /// ```
+    pub fn remove<T: Any + Clone + Send + Sync>(&mut self) -> Option<T> {
+        self.map
+            .as_mut()
+            .and_then(|map| map.remove(&TypeId::of::<T>()))
+            .and_then(|boxed| {
+                (boxed as Box<dyn Any + Send + Sync>)
+                    .downcast()
+                    .ok()
+                    .map(|x| *x)
+            })
+    }
+
+    /// Clear the `CloneTypeMap` of all inserted values.
+    ///
+    /// ```
+    /// # use dora_core::server::typemap::CloneTypeMap;
+    /// let mut map = CloneTypeMap::new();
+    /// map.insert(10_usize);
+    /// map.clear();
+    ///
+    /// assert!(map.get::<usize>().is_none());
+    /// ```
+    pub fn clear(&mut self) {
+        if let Some(ref mut map) = self.map {
+            map.clear();
+        }
+    }
+}
+
+impl Clone for CloneTypeMap {
+    fn clone(&self) -> Self {
+        CloneTypeMap {
+            map: self.map.clone(),
+        }
+    }
+}
+
+/// A value that can be stored in a [`DebugTypeMap`], carrying enough
+/// capability to produce a useful [`fmt::Debug`] rendering.
+trait DebugAny: Any + Send + Sync + fmt::Debug {}
+
+impl<T: Any + Send + Sync + fmt::Debug> DebugAny for T {}
+
+/// An entry in a [`DebugTypeMap`]'s inner storage: the boxed value alongside
+/// the `type_name` captured at insertion time, since a bare `TypeId` can't
+/// be turned back into a readable name for [`fmt::Debug`].
+struct DebugTypeMapEntry {
+    type_name: &'static str,
+    value: Box<dyn DebugAny + Send + Sync>,
+}
+
+type DebugAnyTypeMap = RawHashMap<TypeId, DebugTypeMapEntry, BuildHasherDefault<TypeIdHash>>;
+
+/// A [`TypeMap`] variant whose values are required to implement
+/// [`fmt::Debug`], so the map itself renders its stored values through
+/// [`fmt::Debug`] instead of just listing type names.
+///
+/// ```
+/// # use dora_core::server::typemap::DebugTypeMap;
+/// let mut map = DebugTypeMap::new();
+/// map.insert(10_usize);
+/// assert_eq!(format!("{:?}", map), "{\"usize\": 10}");
+/// ```
+#[derive(Default)]
+pub struct DebugTypeMap {
+    map: Option<Box<DebugAnyTypeMap>>,
+}
+
+impl DebugTypeMap {
+    /// Make a new `DebugTypeMap`, does zero allocation
+    #[inline]
+    pub fn new() -> DebugTypeMap {
+        DebugTypeMap { map: None }
+    }
+
+    /// Insert a type into the map. If the type already exists, it will be
+    /// returned.
+    pub fn insert<T: Send + Sync + fmt::Debug + 'static>(&mut self, val: T) -> Option<T> {
+        self.map
+            .get_or_insert_with(|| Box::new(RawHashMap::default()))
+            .insert(
+                TypeId::of::<T>(),
+                DebugTypeMapEntry {
+                    type_name: std::any::type_name::<T>(),
+                    value: Box::new(val),
+                },
+            )
+            .and_then(|entry| {
+                (entry.value as Box<dyn Any + Send + Sync>)
+                    .downcast()
+                    .ok()
+                    .map(|x| *x)
+            })
+    }
+
+    /// Get a reference to a type previously inserted
+    pub fn get<T: Send + Sync + fmt::Debug + 'static>(&self) -> Option<&T> {
+        self.map
+            .as_ref()
+            .and_then(|map| map.get(&TypeId::of::<T>()))
+            .and_then(|entry| (&*entry.value as &dyn Any).downcast_ref::<T>())
+    }
+
+    /// Get a mutable reference to a type previously inserted
+    pub fn get_mut<T: Send + Sync + fmt::Debug + 'static>(&mut self) -> Option<&mut T> {
+        self.map
+            .as_mut()
+            .and_then(|map| map.get_mut(&TypeId::of::<T>()))
+            .and_then(|entry| (&mut *entry.value as &mut dyn Any).downcast_mut::<T>())
+    }
+
+    /// Remove a type
+    pub fn remove<T: Send + Sync + fmt::Debug + 'static>(&mut self) -> Option<T> {
+        self.map
+            .as_mut()
+            .and_then(|map| map.remove(&TypeId::of::<T>()))
+            .and_then(|entry| {
+                (entry.value as Box<dyn Any + Send + Sync>)
+                    .downcast()
+                    .ok()
+                    .map(|x| *x)
+            })
+    }
+
+    /// Clear the `DebugTypeMap` of all inserted values.
+    pub fn clear(&mut self) {
+        if let Some(ref mut map) = self.map {
+            map.clear();
+        }
+    }
+}
+
+impl fmt::Debug for DebugTypeMap {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let mut dbg = f.debug_map();
+        if let Some(map) = &self.map {
+            for entry in map.values() {
+                dbg.entry(&entry.type_name, &(&*entry.value as &dyn fmt::Debug));
+            }
+        }
+        dbg.finish()
     }
 }